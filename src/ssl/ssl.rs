@@ -11,25 +11,91 @@
  * This file is part of Mesalink.
  */
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Once};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashMap;
 use std::net::TcpStream;
-use std::io::{Read, Write};
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::fs::File;
 use std::ffi::CStr;
 use std::os::unix::io::FromRawFd;
 use std::slice;
 use std::ptr;
-use libc::{c_char, c_int, c_uchar};
+use libc::{c_char, c_int, c_uchar, c_void};
 use rustls;
-use rustls::{Session, Stream};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    RootCertStore, ServerCertVerified, ServerCertVerifier, Session, Stream, TLSError,
+};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use webpki;
 use webpki_roots::TLS_SERVER_ROOTS;
 use ssl::err::{mesalink_push_error, ErrorCode};
 
+/// Certificate file is PEM-encoded, mirroring OpenSSL's `SSL_FILETYPE_PEM`.
+pub const MESALINK_FILETYPE_PEM: c_int = 1;
+/// Certificate file is DER-encoded, mirroring OpenSSL's `SSL_FILETYPE_ASN1`.
+pub const MESALINK_FILETYPE_ASN1: c_int = 2;
+
+pub const MESALINK_VERIFY_NONE: c_int = 0x00;
+pub const MESALINK_VERIFY_PEER: c_int = 0x01;
+pub const MESALINK_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 0x02;
+
+/// Mirrors OpenSSL's `X509_V_OK`/`X509_V_ERR_*` verify result codes.
+pub const MESALINK_X509_V_OK: c_int = 0;
+pub const MESALINK_X509_V_ERR_UNSPECIFIED: c_int = 1;
+
+/* Wire-format version numbers, mirroring OpenSSL's TLS1_2_VERSION etc. */
+pub const MESALINK_TLS1_2_VERSION: c_int = 0x0303;
+pub const MESALINK_TLS1_3_VERSION: c_int = 0x0304;
+
 const MAGIC: u32 = 0xc0d4c5a9;
 
+/* The lowest and highest protocol versions this build of rustls supports;
+ * `mesalink_CTX_set_{min,max}_proto_version(.., 0)` snap to these. */
+const LOWEST_SUPPORTED_VERSION: rustls::ProtocolVersion = rustls::ProtocolVersion::TLSv1_2;
+const HIGHEST_SUPPORTED_VERSION: rustls::ProtocolVersion = rustls::ProtocolVersion::TLSv1_3;
+
+fn protocol_version_value(version: rustls::ProtocolVersion) -> u16 {
+    match version {
+        rustls::ProtocolVersion::TLSv1_2 => MESALINK_TLS1_2_VERSION as u16,
+        rustls::ProtocolVersion::TLSv1_3 => MESALINK_TLS1_3_VERSION as u16,
+        _ => 0,
+    }
+}
+
+fn protocol_version_from_c_int(ver: c_int) -> Option<rustls::ProtocolVersion> {
+    match ver {
+        MESALINK_TLS1_2_VERSION => Some(rustls::ProtocolVersion::TLSv1_2),
+        MESALINK_TLS1_3_VERSION => Some(rustls::ProtocolVersion::TLSv1_3),
+        _ => None,
+    }
+}
+
+/// All versions this build supports, highest first, between `min` and
+/// `max` inclusive.
+fn versions_in_range(
+    min: rustls::ProtocolVersion,
+    max: rustls::ProtocolVersion,
+) -> Vec<rustls::ProtocolVersion> {
+    let min = protocol_version_value(min);
+    let max = protocol_version_value(max);
+    [HIGHEST_SUPPORTED_VERSION, LOWEST_SUPPORTED_VERSION]
+        .iter()
+        .cloned()
+        .filter(|&v| {
+            let v = protocol_version_value(v);
+            v >= min && v <= max
+        })
+        .collect()
+}
+
 #[repr(C)]
 pub struct MESALINK_METHOD {
     magic: u32,
-    tls_version: rustls::ProtocolVersion,
+    min_version: rustls::ProtocolVersion,
+    max_version: rustls::ProtocolVersion,
 }
 
 #[repr(C)]
@@ -37,16 +103,157 @@ pub struct MESALINK_CTX {
     magic: u32,
     client_config: Arc<rustls::ClientConfig>,
     server_config: Arc<rustls::ServerConfig>,
+    /* Staged from `mesalink_CTX_use_certificate_chain_file`, consumed once
+     * `mesalink_CTX_use_PrivateKey_file` supplies the matching key. */
+    cert_chain: Option<Vec<rustls::Certificate>>,
+    /* Populated by `mesalink_CTX_load_verify_locations` and consumed by
+     * `mesalink_CTX_set_verify` when enabling mutual TLS on the server
+     * side; empty until then, which accepts no client certificate. */
+    client_auth_roots: RootCertStore,
+    min_proto_version: rustls::ProtocolVersion,
+    max_proto_version: rustls::ProtocolVersion,
+}
+
+/// The two roles a session can be driven in. MesaLink only ever creates a
+/// `rustls::ClientSession` from `mesalink_SSL_connect` or a
+/// `rustls::ServerSession` from `mesalink_SSL_accept`, so this enum (rather
+/// than making `MESALINK_SSL` itself generic over `Session`) is enough to
+/// cover every instance that exists at runtime, while keeping the
+/// `mesalink_SSL_*` FFI entry points themselves non-generic — a generic
+/// `#[no_mangle] extern "C" fn` has no C-callable symbol at all, since it is
+/// never monomorphized unless something in this crate instantiates it with
+/// a concrete type, which nothing did.
+enum SslSession {
+    Client(rustls::ClientSession),
+    Server(rustls::ServerSession),
+}
+
+impl SslSession {
+    fn wants_write(&self) -> bool {
+        match self {
+            SslSession::Client(session) => session.wants_write(),
+            SslSession::Server(session) => session.wants_write(),
+        }
+    }
+
+    fn is_handshaking(&self) -> bool {
+        match self {
+            SslSession::Client(session) => session.is_handshaking(),
+            SslSession::Server(session) => session.is_handshaking(),
+        }
+    }
+
+    fn write_tls(&mut self, wr: &mut dyn Write) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => session.write_tls(wr),
+            SslSession::Server(session) => session.write_tls(wr),
+        }
+    }
+
+    fn read_tls(&mut self, rd: &mut dyn Read) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => session.read_tls(rd),
+            SslSession::Server(session) => session.read_tls(rd),
+        }
+    }
+
+    fn process_new_packets(&mut self) -> Result<(), TLSError> {
+        match self {
+            SslSession::Client(session) => session.process_new_packets(),
+            SslSession::Server(session) => session.process_new_packets(),
+        }
+    }
+
+    fn send_close_notify(&mut self) {
+        match self {
+            SslSession::Client(session) => session.send_close_notify(),
+            SslSession::Server(session) => session.send_close_notify(),
+        }
+    }
+
+    fn get_peer_certificates(&self) -> Option<Vec<rustls::Certificate>> {
+        match self {
+            SslSession::Client(session) => session.get_peer_certificates(),
+            SslSession::Server(session) => session.get_peer_certificates(),
+        }
+    }
+
+    fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        match self {
+            SslSession::Client(session) => session.get_alpn_protocol(),
+            SslSession::Server(session) => session.get_alpn_protocol(),
+        }
+    }
+
+    /// Reads/writes ciphertext to `socket` and drives the handshake as
+    /// needed, mirroring `rustls::Stream`'s `Read`/`Write` impls. Built
+    /// fresh per call rather than stored, since `Stream<'_, S, T>`'s
+    /// concrete type differs between the `Client` and `Server` variants.
+    fn read(&mut self, socket: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => Stream::new(session, socket).read(buf),
+            SslSession::Server(session) => Stream::new(session, socket).read(buf),
+        }
+    }
+
+    fn write(&mut self, socket: &mut TcpStream, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => Stream::new(session, socket).write(buf),
+            SslSession::Server(session) => Stream::new(session, socket).write(buf),
+        }
+    }
+
+    fn flush(&mut self, socket: &mut TcpStream) -> io::Result<()> {
+        match self {
+            SslSession::Client(session) => Stream::new(session, socket).flush(),
+            SslSession::Server(session) => Stream::new(session, socket).flush(),
+        }
+    }
+
+    /// Reads decrypted application data straight out of the session in
+    /// memory-BIO mode, where there is no socket for a `Stream` to pump
+    /// ciphertext through — `ClientSession`/`ServerSession` implement
+    /// plaintext `Read`/`Write` themselves, fed by `read_tls`/`write_tls`.
+    fn read_plaintext(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => session.read(buf),
+            SslSession::Server(session) => session.read(buf),
+        }
+    }
+
+    /// Writes application data straight into the session in memory-BIO
+    /// mode; the caller drains the resulting ciphertext with
+    /// `mesalink_SSL_write_tls`.
+    fn write_plaintext(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SslSession::Client(session) => session.write(buf),
+            SslSession::Server(session) => session.write(buf),
+        }
+    }
 }
 
 #[repr(C)]
-pub struct MESALINK_SSL<'a, S: 'a + Session, T: 'a + Read + Write> {
+pub struct MESALINK_SSL<'a> {
     magic: u32,
     context: &'a mut MESALINK_CTX,
     hostname: Option<&'a CStr>,
     socket: Option<TcpStream>,
-    session: Option<S>,
-    stream: Option<Stream<'a, S, T>>,
+    session: Option<SslSession>,
+    /* Ciphertext queued by the TLS state machine, awaiting a caller pump
+     * via `mesalink_SSL_write_tls` when the session has no owned socket. */
+    tls_output_buffer: Vec<u8>,
+    last_io_error: SslErrorCode,
+    sent_close_notify: bool,
+    received_close_notify: bool,
+}
+
+/// An opaque wrapper around a peer's leaf certificate, handed out by
+/// `mesalink_SSL_get_peer_certificate` for callers doing certificate
+/// inspection or pinning.
+#[repr(C)]
+pub struct MESALINK_X509 {
+    magic: u32,
+    der: Vec<u8>,
 }
 
 pub enum SslConstants {
@@ -54,6 +261,39 @@ pub enum SslConstants {
     SslSuccess = 1,
 }
 
+/// Mirrors the OpenSSL `SSL_get_error` result codes so callers can tell
+/// retryable conditions (`WANT_READ`/`WANT_WRITE`) apart from fatal ones.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum SslErrorCode {
+    SslErrorNone = 0,
+    SslErrorSsl = 1,
+    SslErrorWantRead = 2,
+    SslErrorWantWrite = 3,
+    SslErrorSyscall = 5,
+    SslErrorZeroReturn = 6,
+}
+
+fn io_error_to_ssl_error(e: &io::Error, is_write: bool) -> SslErrorCode {
+    match e.kind() {
+        io::ErrorKind::WouldBlock => {
+            if is_write {
+                SslErrorCode::SslErrorWantWrite
+            } else {
+                SslErrorCode::SslErrorWantRead
+            }
+        }
+        /* rustls raises `UnexpectedEof` (falls through to `_` below) when
+         * the transport closes without a valid `close_notify`, which is
+         * the truncation case it's designed to let callers detect —
+         * distinct from a clean shutdown, which surfaces as `Ok(0)` and is
+         * mapped to `SslErrorZeroReturn` by the read path directly.
+         * Folding both into `SslErrorZeroReturn` here would hide that
+         * distinction from the caller. */
+        _ => SslErrorCode::SslErrorSyscall,
+    }
+}
+
 macro_rules! sanitize_ptr_return_null {
     ( $ptr_var:ident ) => {
         if $ptr_var.is_null() {
@@ -66,6 +306,18 @@ macro_rules! sanitize_ptr_return_null {
     }
 }
 
+macro_rules! sanitize_ptr_return_zero {
+    ( $ptr_var:ident ) => {
+        if $ptr_var.is_null() {
+            return 0;
+        }
+        let obj = unsafe { &* $ptr_var };
+        if obj.magic != MAGIC {
+            return 0;
+        }
+    }
+}
+
 macro_rules! sanitize_ptr_return_fail {
     ( $ptr_var:ident ) => {
         if $ptr_var.is_null() {
@@ -78,6 +330,55 @@ macro_rules! sanitize_ptr_return_fail {
     }
 }
 
+/// A session is born from `mesalink_SSL_new` as a `Box<MESALINK_SSL>`,
+/// registered here under a fresh `u64` id, and from then on the FFI
+/// surface only ever hands callers that id. A forged or stale id is just
+/// a lookup miss instead of a dereference of attacker-controlled memory,
+/// which matters once the handle crosses a trust boundary (e.g. into an
+/// SGX enclave that has no business touching MesaLink's heap directly).
+struct SessionTable {
+    handles: Mutex<HashMap<u64, usize>>,
+    next_id: AtomicU64,
+}
+
+fn session_table() -> &'static SessionTable {
+    static INIT: Once = Once::new();
+    static mut TABLE: *const SessionTable = ptr::null();
+    unsafe {
+        INIT.call_once(|| {
+            let table = Box::new(SessionTable {
+                handles: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(1),
+            });
+            TABLE = Box::into_raw(table);
+        });
+        &*TABLE
+    }
+}
+
+/// Validates `$id_var` against the session table and binds `ssl_ptr` to
+/// the matching `*mut MESALINK_SSL`, or returns `SslConstants::SslFailure`
+/// if the id is unknown or stale.
+macro_rules! sanitize_handle_return_fail {
+    ( $id_var:ident ) => {
+        let ssl_ptr = match session_table().handles.lock().unwrap().get(&$id_var) {
+            Some(&addr) => addr as *mut MESALINK_SSL<'_>,
+            None => return SslConstants::SslFailure as c_int,
+        };
+        let obj = unsafe { &*ssl_ptr };
+        if obj.magic != MAGIC {
+            return SslConstants::SslFailure as c_int;
+        }
+    }
+}
+
+fn register_ssl_handle(ssl_ptr: *mut MESALINK_SSL<'static>) -> u64 {
+    let table = session_table();
+    let id = table.next_id.fetch_add(1, Ordering::SeqCst);
+    table.handles.lock().unwrap().insert(id, ssl_ptr as usize);
+    id
+}
+
 #[no_mangle]
 pub extern "C" fn mesalink_library_init() -> c_int {
     /* compatibility only */
@@ -117,7 +418,8 @@ pub extern "C" fn mesalink_TLSv1_1_client_method() -> *mut MESALINK_METHOD {
 pub extern "C" fn mesalink_TLSv1_2_client_method() -> *mut MESALINK_METHOD {
     let method = MESALINK_METHOD {
         magic: MAGIC,
-        tls_version: rustls::ProtocolVersion::TLSv1_2,
+        min_version: rustls::ProtocolVersion::TLSv1_2,
+        max_version: rustls::ProtocolVersion::TLSv1_2,
     };
     Box::into_raw(Box::new(method))
 }
@@ -126,7 +428,22 @@ pub extern "C" fn mesalink_TLSv1_2_client_method() -> *mut MESALINK_METHOD {
 pub extern "C" fn mesalink_TLSv1_3_client_method() -> *mut MESALINK_METHOD {
     let method = MESALINK_METHOD {
         magic: MAGIC,
-        tls_version: rustls::ProtocolVersion::TLSv1_3,
+        min_version: rustls::ProtocolVersion::TLSv1_3,
+        max_version: rustls::ProtocolVersion::TLSv1_3,
+    };
+    Box::into_raw(Box::new(method))
+}
+
+/// A method that negotiates any protocol version this build supports
+/// (currently TLS 1.2 through TLS 1.3), mirroring how modern OpenSSL
+/// callers configure versions via `TLS_method()` plus
+/// `SSL_CTX_set_{min,max}_proto_version` rather than pinning one version.
+#[no_mangle]
+pub extern "C" fn mesalink_TLS_method() -> *mut MESALINK_METHOD {
+    let method = MESALINK_METHOD {
+        magic: MAGIC,
+        min_version: LOWEST_SUPPORTED_VERSION,
+        max_version: HIGHEST_SUPPORTED_VERSION,
     };
     Box::into_raw(Box::new(method))
 }
@@ -135,27 +452,421 @@ pub extern "C" fn mesalink_TLSv1_3_client_method() -> *mut MESALINK_METHOD {
 pub extern "C" fn mesalink_CTX_new(method_ptr: *mut MESALINK_METHOD) -> *mut MESALINK_CTX {
     sanitize_ptr_return_null!(method_ptr);
     let method = unsafe { &*method_ptr };
+    let versions = versions_in_range(method.min_version, method.max_version);
     let mut client_config = rustls::ClientConfig::new();
-    client_config.versions = vec![method.tls_version];
+    client_config.versions = versions.clone();
     client_config
         .root_store
         .add_server_trust_anchors(&TLS_SERVER_ROOTS);
     let mut server_config = rustls::ServerConfig::new();
-    server_config.versions = vec![method.tls_version];
+    server_config.versions = versions;
     let context = MESALINK_CTX {
         magic: MAGIC,
         client_config: Arc::new(client_config),
         server_config: Arc::new(server_config),
+        cert_chain: None,
+        client_auth_roots: RootCertStore::empty(),
+        min_proto_version: method.min_version,
+        max_proto_version: method.max_version,
     };
     let _ = unsafe { Box::from_raw(method_ptr) };
     Box::into_raw(Box::new(context))
 }
 
+/// Recomputes and re-installs the negotiable version list on both configs
+/// from `ctx`'s current min/max bounds.
+fn apply_proto_versions(ctx: &mut MESALINK_CTX) -> c_int {
+    let versions = versions_in_range(ctx.min_proto_version, ctx.max_proto_version);
+    if versions.is_empty() {
+        /* min > max (e.g. raising the min past a max pinned by
+         * mesalink_TLSv1_2_client_method) would otherwise silently install
+         * an empty version list, which only surfaces as a handshake
+         * failure with no obvious cause much later. */
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    let client_config = Arc::get_mut(&mut ctx.client_config);
+    let server_config = Arc::get_mut(&mut ctx.server_config);
+    match (client_config, server_config) {
+        (Some(client_config), Some(server_config)) => {
+            client_config.versions = versions.clone();
+            server_config.versions = versions;
+            SslConstants::SslSuccess as c_int
+        }
+        _ => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// Sets the lowest protocol version `ctx` will negotiate. `ver` is a
+/// `MESALINK_TLS1_*_VERSION` constant, or 0 to remove the lower bound.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_set_min_proto_version(
+    ctx_ptr: *mut MESALINK_CTX,
+    ver: c_int,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    ctx.min_proto_version = if ver == 0 {
+        LOWEST_SUPPORTED_VERSION
+    } else {
+        match protocol_version_from_c_int(ver) {
+            Some(version) => version,
+            None => {
+                mesalink_push_error(ErrorCode::General);
+                return SslConstants::SslFailure as c_int;
+            }
+        }
+    };
+    apply_proto_versions(ctx)
+}
+
+/// Sets the highest protocol version `ctx` will negotiate. `ver` is a
+/// `MESALINK_TLS1_*_VERSION` constant, or 0 to remove the upper bound.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_set_max_proto_version(
+    ctx_ptr: *mut MESALINK_CTX,
+    ver: c_int,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    ctx.max_proto_version = if ver == 0 {
+        HIGHEST_SUPPORTED_VERSION
+    } else {
+        match protocol_version_from_c_int(ver) {
+            Some(version) => version,
+            None => {
+                mesalink_push_error(ErrorCode::General);
+                return SslConstants::SslFailure as c_int;
+            }
+        }
+    };
+    apply_proto_versions(ctx)
+}
+
+/// Loads a PEM certificate chain to be presented during the handshake.
+/// Must be followed by `mesalink_CTX_use_PrivateKey_file` with the
+/// matching key before the chain takes effect.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_use_certificate_chain_file(
+    ctx_ptr: *mut MESALINK_CTX,
+    path_ptr: *const c_char,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    if path_ptr.is_null() {
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    let path = unsafe { CStr::from_ptr(path_ptr) };
+    let chain = path
+        .to_str()
+        .ok()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| certs(&mut BufReader::new(file)).ok());
+    match chain {
+        Some(chain) => {
+            ctx.cert_chain = Some(chain);
+            SslConstants::SslSuccess as c_int
+        }
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// Reads `path` as a PEM file and returns the first key found, trying
+/// PKCS#8 then PKCS#1 (RSA), mirroring how OpenSSL's `SSL_FILETYPE_PEM`
+/// accepts either.
+fn read_pem_private_key(path: &str) -> Option<rustls::PrivateKey> {
+    let keys = File::open(path)
+        .ok()
+        .and_then(|file| pkcs8_private_keys(&mut BufReader::new(file)).ok())
+        .filter(|keys| !keys.is_empty());
+    let keys = match keys {
+        Some(keys) => Some(keys),
+        None => File::open(path)
+            .ok()
+            .and_then(|file| rsa_private_keys(&mut BufReader::new(file)).ok()),
+    };
+    keys.and_then(|keys| keys.into_iter().next())
+}
+
+/// Reads `path` as a single raw DER-encoded key, mirroring OpenSSL's
+/// `SSL_FILETYPE_ASN1`. `rustls::PrivateKey` is just the raw DER bytes;
+/// `ServerConfig::set_single_cert`/`ClientConfig::set_single_client_cert`
+/// try PKCS#8 then PKCS#1 against it the same way they do for a PEM key.
+fn read_der_private_key(path: &str) -> Option<rustls::PrivateKey> {
+    let mut der = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut der).ok()?;
+    Some(rustls::PrivateKey(der))
+}
+
+/// Loads the private key matching the chain staged by
+/// `mesalink_CTX_use_certificate_chain_file` and installs both into the
+/// client and server configs so the context can present a certificate
+/// either as a TLS server or as a client performing mutual TLS.
+/// `file_type` is `MESALINK_FILETYPE_PEM` or `MESALINK_FILETYPE_ASN1`.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_use_PrivateKey_file(
+    ctx_ptr: *mut MESALINK_CTX,
+    path_ptr: *const c_char,
+    file_type: c_int,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    let chain = match ctx.cert_chain.clone() {
+        Some(chain) => chain,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    if path_ptr.is_null() {
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    let path = unsafe { CStr::from_ptr(path_ptr) };
+    let key = path.to_str().ok().and_then(|path| match file_type {
+        MESALINK_FILETYPE_ASN1 => read_der_private_key(path),
+        _ => read_pem_private_key(path),
+    });
+    let key = match key {
+        Some(key) => key,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+
+    let server_ok = Arc::get_mut(&mut ctx.server_config)
+        .map(|server_config| server_config.set_single_cert(chain.clone(), key.clone()).is_ok())
+        .unwrap_or(false);
+    let client_ok = Arc::get_mut(&mut ctx.client_config)
+        .map(|client_config| {
+            client_config.set_single_client_cert(chain, key);
+            true
+        })
+        .unwrap_or(false);
+
+    if server_ok || client_ok {
+        SslConstants::SslSuccess as c_int
+    } else {
+        mesalink_push_error(ErrorCode::General);
+        SslConstants::SslFailure as c_int
+    }
+}
+
+/// Loads the PEM-encoded CA certificates in `ca_file` that `ctx` will trust
+/// when verifying a client certificate under mutual TLS. Must be called
+/// before `mesalink_CTX_set_verify` with a mode other than
+/// `MESALINK_VERIFY_NONE`, or every client certificate will fail
+/// verification against the (otherwise empty) root store.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_load_verify_locations(
+    ctx_ptr: *mut MESALINK_CTX,
+    ca_file_ptr: *const c_char,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    if ca_file_ptr.is_null() {
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    let path = unsafe { CStr::from_ptr(ca_file_ptr) };
+    let roots = path
+        .to_str()
+        .ok()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| certs(&mut BufReader::new(file)).ok());
+    let roots = match roots {
+        Some(roots) => roots,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    let mut store = RootCertStore::empty();
+    let (added, _) = store.add_parsable_certificates(&roots);
+    if added == 0 {
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    ctx.client_auth_roots = store;
+    SslConstants::SslSuccess as c_int
+}
+
+/// Enables mutual TLS on the server side of `ctx`. `mode` is a bitmask of
+/// `MESALINK_VERIFY_*`; when `MESALINK_VERIFY_FAIL_IF_NO_PEER_CERT` is set
+/// the handshake is rejected unless the client presents a certificate,
+/// otherwise an anonymous client is still accepted. `callback` is accepted
+/// for OpenSSL API compatibility but is currently unused. The roots
+/// presented client certificates are validated against come from
+/// `mesalink_CTX_load_verify_locations`.
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_new<'a, S: Session, T: Read + Write>(
+pub extern "C" fn mesalink_CTX_set_verify(
     ctx_ptr: *mut MESALINK_CTX,
-) -> *mut MESALINK_SSL<'a, S, T> {
-    sanitize_ptr_return_null!(ctx_ptr);
+    mode: c_int,
+    _callback: *mut c_void,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    let client_auth_roots = ctx.client_auth_roots.clone();
+    let verifier: Arc<dyn rustls::ClientCertVerifier> = if mode == MESALINK_VERIFY_NONE {
+        Arc::new(NoClientAuth::new())
+    } else if mode & MESALINK_VERIFY_FAIL_IF_NO_PEER_CERT != 0 {
+        Arc::new(AllowAnyAuthenticatedClient::new(client_auth_roots))
+    } else {
+        Arc::new(AllowAnyAnonymousOrAuthenticatedClient::new(client_auth_roots))
+    };
+    match Arc::get_mut(&mut ctx.server_config) {
+        Some(server_config) => {
+            server_config.set_client_certificate_verifier(verifier);
+            SslConstants::SslSuccess as c_int
+        }
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// A caller-supplied replacement for rustls's normal certificate chain
+/// validation. `arg` is an opaque pointer the caller can use to recover
+/// state; `der_ptr`/`der_len` describe the peer's leaf certificate.
+/// Returns `SslConstants::SslSuccess` to accept the certificate.
+pub type MesalinkCertVerifyCallback =
+    extern "C" fn(arg: *mut c_void, der_ptr: *const c_uchar, der_len: c_int) -> c_int;
+
+struct CustomCertVerifier {
+    callback: MesalinkCertVerifyCallback,
+    arg: usize,
+}
+
+/* `arg` is an opaque caller-owned pointer passed straight back through the
+ * FFI boundary on every call; MesaLink never dereferences it itself. */
+unsafe impl Send for CustomCertVerifier {}
+unsafe impl Sync for CustomCertVerifier {}
+
+impl ServerCertVerifier for CustomCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let (der_ptr, der_len) = match presented_certs.first() {
+            Some(cert) => (cert.0.as_ptr(), cert.0.len() as c_int),
+            None => (ptr::null(), 0),
+        };
+        let result = (self.callback)(self.arg as *mut c_void, der_ptr, der_len);
+        if result == SslConstants::SslSuccess as c_int {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "certificate rejected by custom verifier".to_string(),
+            ))
+        }
+    }
+}
+
+/// Installs a custom certificate verifier on `ctx`'s client config,
+/// letting the caller override trust evaluation entirely (e.g. for
+/// certificate pinning or connecting to hosts with private CAs) —
+/// mirroring how security-framework's SecureTransport lets clients
+/// override trust evaluation. This bypasses rustls's normal chain
+/// validation, hence going through `ClientConfig::dangerous()`.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_set_cert_verify_callback(
+    ctx_ptr: *mut MESALINK_CTX,
+    callback: MesalinkCertVerifyCallback,
+    arg: *mut c_void,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    match Arc::get_mut(&mut ctx.client_config) {
+        Some(client_config) => {
+            let verifier = CustomCertVerifier {
+                callback,
+                arg: arg as usize,
+            };
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+            SslConstants::SslSuccess as c_int
+        }
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// Decodes the OpenSSL wire format for ALPN protocol lists: a
+/// concatenation of `{len: u8, name: [u8; len]}` entries.
+fn parse_alpn_wire_format(buf: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut protos = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let len = buf[i] as usize;
+        i += 1;
+        if len == 0 || i + len > buf.len() {
+            return None;
+        }
+        protos.push(buf[i..i + len].to_vec());
+        i += len;
+    }
+    Some(protos)
+}
+
+/// Sets the list of application protocols `ctx` will offer (client) or
+/// accept (server) during ALPN negotiation, e.g. to front an HTTP/2
+/// server. `protos` uses the OpenSSL wire format.
+#[no_mangle]
+pub extern "C" fn mesalink_CTX_set_alpn_protos(
+    ctx_ptr: *mut MESALINK_CTX,
+    protos_ptr: *const c_uchar,
+    protos_len: c_int,
+) -> c_int {
+    sanitize_ptr_return_fail!(ctx_ptr);
+    let ctx = unsafe { &mut *ctx_ptr };
+    if protos_ptr.is_null() || protos_len <= 0 {
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    let buf = unsafe { slice::from_raw_parts(protos_ptr, protos_len as usize) };
+    let protos = match parse_alpn_wire_format(buf) {
+        Some(protos) => protos,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    let client_config = Arc::get_mut(&mut ctx.client_config);
+    let server_config = Arc::get_mut(&mut ctx.server_config);
+    match (client_config, server_config) {
+        (Some(client_config), Some(server_config)) => {
+            client_config.alpn_protocols = protos.clone();
+            server_config.alpn_protocols = protos;
+            SslConstants::SslSuccess as c_int
+        }
+        _ => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// Creates a new SSL session bound to `ctx` and returns an integer handle
+/// for use with the rest of the `mesalink_SSL_*` API, rather than a raw
+/// pointer (see `SessionTable` above).
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_new(ctx_ptr: *mut MESALINK_CTX) -> u64 {
+    sanitize_ptr_return_zero!(ctx_ptr);
     let ctx = unsafe { &mut *ctx_ptr };
     let ssl = MESALINK_SSL {
         magic: MAGIC,
@@ -163,17 +874,21 @@ pub extern "C" fn mesalink_SSL_new<'a, S: Session, T: Read + Write>(
         hostname: None,
         socket: None,
         session: None,
-        stream: None,
+        tls_output_buffer: Vec::new(),
+        last_io_error: SslErrorCode::SslErrorNone,
+        sent_close_notify: false,
+        received_close_notify: false,
     };
-    Box::into_raw(Box::new(ssl))
+    let ssl_ptr: *mut MESALINK_SSL<'static> = Box::into_raw(Box::new(ssl));
+    register_ssl_handle(ssl_ptr)
 }
 
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_set_tlsext_host_name<S: Session, T: Read + Write>(
-    ssl_ptr: *mut MESALINK_SSL<S, T>,
+pub extern "C" fn mesalink_SSL_set_tlsext_host_name(
+    ssl_id: u64,
     hostname_ptr: *const c_char,
 ) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
     if hostname_ptr.is_null() {
         mesalink_push_error(ErrorCode::General);
@@ -185,63 +900,178 @@ pub extern "C" fn mesalink_SSL_set_tlsext_host_name<S: Session, T: Read + Write>
 }
 
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_set_fd<S: Session, T: Read + Write>(
-    ssl_ptr: *mut MESALINK_SSL<S, T>,
-    fd: c_int,
-) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+pub extern "C" fn mesalink_SSL_set_fd(ssl_id: u64, fd: c_int) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
     let socket = unsafe { TcpStream::from_raw_fd(fd) };
     ssl.socket = Some(socket);
     SslConstants::SslSuccess as c_int
 }
 
+/// Switches `ssl` into memory-BIO mode: the session no longer owns a
+/// `TcpStream` and the caller is responsible for pumping ciphertext in and
+/// out via `mesalink_SSL_read_tls`/`mesalink_SSL_write_tls`. Useful in
+/// sandboxed environments (e.g. an SGX enclave) where the code running TLS
+/// has no direct socket access.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_set_bio_mem(ssl_id: u64) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    ssl.socket = None;
+    SslConstants::SslSuccess as c_int
+}
+
+/// Drives the handshake state machine without touching a socket: drains
+/// outbound ciphertext into the internal output buffer (fetched by the
+/// caller via `mesalink_SSL_write_tls`) until the handshake either
+/// completes or needs more input, which the caller supplies via
+/// `mesalink_SSL_read_tls`.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_do_handshake(ssl_id: u64) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    let session = match ssl.session.as_mut() {
+        Some(session) => session,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    while session.wants_write() {
+        if session.write_tls(&mut ssl.tls_output_buffer).is_err() {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    }
+    if session.is_handshaking() {
+        /* Need more ciphertext from the peer before we can proceed; the
+         * caller should feed bytes in via `mesalink_SSL_read_tls` and
+         * call us again. `mesalink_SSL_get_error` needs to see WANT_READ
+         * here rather than stale state from a previous call, so an event
+         * loop can actually tell this apart from a fatal failure. */
+        ssl.last_io_error = SslErrorCode::SslErrorWantRead;
+        mesalink_push_error(ErrorCode::General);
+        return SslConstants::SslFailure as c_int;
+    }
+    ssl.last_io_error = SslErrorCode::SslErrorNone;
+    SslConstants::SslSuccess as c_int
+}
+
+/// Feeds raw ciphertext received from the transport into the session, for
+/// use in memory-BIO mode where MesaLink has no socket of its own.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_read_tls(
+    ssl_id: u64,
+    buf_ptr: *const c_uchar,
+    buf_len: c_int,
+) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    let mut buf = unsafe { slice::from_raw_parts(buf_ptr, buf_len as usize) };
+    let session = match ssl.session.as_mut() {
+        Some(session) => session,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    match session.read_tls(&mut buf) {
+        Ok(count) => match session.process_new_packets() {
+            Ok(_) => count as c_int,
+            Err(_) => {
+                mesalink_push_error(ErrorCode::General);
+                SslConstants::SslFailure as c_int
+            }
+        },
+        Err(_) => {
+            mesalink_push_error(ErrorCode::General);
+            SslConstants::SslFailure as c_int
+        }
+    }
+}
+
+/// Drains ciphertext the session has queued for the peer into `buf`, for
+/// use in memory-BIO mode where the caller owns the transport.
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_connect(
-    ssl_ptr: *mut MESALINK_SSL<rustls::ClientSession, TcpStream>,
+pub extern "C" fn mesalink_SSL_write_tls(
+    ssl_id: u64,
+    buf_ptr: *mut c_uchar,
+    buf_len: c_int,
 ) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    let buf = unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len as usize) };
+    if let Some(session) = ssl.session.as_mut() {
+        while session.wants_write() {
+            if session.write_tls(&mut ssl.tls_output_buffer).is_err() {
+                mesalink_push_error(ErrorCode::General);
+                return SslConstants::SslFailure as c_int;
+            }
+        }
+    }
+    let count = ::std::cmp::min(buf.len(), ssl.tls_output_buffer.len());
+    buf[..count].copy_from_slice(&ssl.tls_output_buffer[..count]);
+    ssl.tls_output_buffer.drain(..count);
+    count as c_int
+}
+
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_connect(ssl_id: u64) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
     if let Some(hostname) = ssl.hostname {
         if let Ok(hostname_str) = hostname.to_str() {
             let session = rustls::ClientSession::new(&ssl.context.client_config, hostname_str);
-            ssl.session = Some(session);
-            let stream = Stream::new(ssl.session.as_mut().unwrap(), ssl.socket.as_mut().unwrap());
-            ssl.stream = Some(stream);
+            ssl.session = Some(SslSession::Client(session));
+            ssl.last_io_error = SslErrorCode::SslErrorNone;
             return SslConstants::SslSuccess as c_int;
         }
     }
+    ssl.last_io_error = SslErrorCode::SslErrorSsl;
     mesalink_push_error(ErrorCode::General);
     SslConstants::SslFailure as c_int
 }
 
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_accept(
-    ssl_ptr: *mut MESALINK_SSL<rustls::ServerSession, TcpStream>,
-) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+pub extern "C" fn mesalink_SSL_accept(ssl_id: u64) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
-
     let session = rustls::ServerSession::new(&ssl.context.server_config);
-    ssl.session = Some(session);
-    let stream = Stream::new(ssl.session.as_mut().unwrap(), ssl.socket.as_mut().unwrap());
-    ssl.stream = Some(stream);
+    ssl.session = Some(SslSession::Server(session));
     SslConstants::SslSuccess as c_int
 }
 
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_read<S: Session, T: Read + Write>(
-    ssl_ptr: *mut MESALINK_SSL<S, T>,
-    buf_ptr: *mut c_uchar,
-    buf_len: c_int,
-) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+pub extern "C" fn mesalink_SSL_read(ssl_id: u64, buf_ptr: *mut c_uchar, buf_len: c_int) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
     let mut buf = unsafe { slice::from_raw_parts_mut(buf_ptr, buf_len as usize) };
-    let stream = ssl.stream.as_mut().unwrap();
-    match stream.read(&mut buf) {
-        Ok(count) => count as c_int,
-        Err(_) => {
+    let socket = ssl.socket.as_mut();
+    let session = match ssl.session.as_mut() {
+        Some(session) => session,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    /* In memory-BIO mode there is no socket: app data is read straight out
+     * of the session's own plaintext buffer, fed by mesalink_SSL_read_tls. */
+    let result = match socket {
+        Some(socket) => session.read(socket, &mut buf),
+        None => session.read_plaintext(&mut buf),
+    };
+    match result {
+        Ok(0) => {
+            ssl.received_close_notify = true;
+            ssl.last_io_error = SslErrorCode::SslErrorZeroReturn;
+            SslConstants::SslFailure as c_int
+        }
+        Ok(count) => {
+            ssl.last_io_error = SslErrorCode::SslErrorNone;
+            count as c_int
+        }
+        Err(e) => {
+            ssl.last_io_error = io_error_to_ssl_error(&e, false);
             mesalink_push_error(ErrorCode::General);
             SslConstants::SslFailure as c_int
         }
@@ -249,30 +1079,211 @@ pub extern "C" fn mesalink_SSL_read<S: Session, T: Read + Write>(
 }
 
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_write<S: Session, T: Read + Write>(
-    ssl_ptr: *mut MESALINK_SSL<S, T>,
-    buf_ptr: *const c_uchar,
-    buf_len: c_int,
-) -> c_int {
-    sanitize_ptr_return_fail!(ssl_ptr);
+pub extern "C" fn mesalink_SSL_write(ssl_id: u64, buf_ptr: *const c_uchar, buf_len: c_int) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
     let ssl = unsafe { &mut *ssl_ptr };
     let buf = unsafe { slice::from_raw_parts(buf_ptr, buf_len as usize) };
-    let stream = ssl.stream.as_mut().unwrap();
-    match stream.write(buf) {
-        Ok(count) => count as c_int,
-        Err(_) => {
+    let socket = ssl.socket.as_mut();
+    let session = match ssl.session.as_mut() {
+        Some(session) => session,
+        None => {
+            mesalink_push_error(ErrorCode::General);
+            return SslConstants::SslFailure as c_int;
+        }
+    };
+    /* In memory-BIO mode there is no socket: app data is written straight
+     * into the session's plaintext buffer, drained via write_tls. */
+    let result = match socket {
+        Some(socket) => session.write(socket, buf),
+        None => session.write_plaintext(buf),
+    };
+    match result {
+        Ok(count) => {
+            ssl.last_io_error = SslErrorCode::SslErrorNone;
+            count as c_int
+        }
+        Err(e) => {
+            ssl.last_io_error = io_error_to_ssl_error(&e, true);
             mesalink_push_error(ErrorCode::General);
             SslConstants::SslFailure as c_int
         }
     }
 }
 
+/// OpenSSL-compatible `SSL_get_error`: inspects the outcome of the most
+/// recent `mesalink_SSL_read`/`write`/`connect` call (`ret`) and reports
+/// whether it is retryable (`WANT_READ`/`WANT_WRITE`) or fatal, so
+/// non-blocking callers and async wrappers can drive an event loop.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_get_error(ssl_id: u64, ret: c_int) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    if ret > 0 {
+        return SslErrorCode::SslErrorNone as c_int;
+    }
+    ssl.last_io_error as c_int
+}
+
+/// Reads back the protocol negotiated via ALPN after a completed
+/// handshake. `*out_ptr` is set to `NULL` and `*out_len_ptr` to 0 if no
+/// protocol was negotiated.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_get0_alpn_selected(
+    ssl_id: u64,
+    out_ptr: *mut *const c_uchar,
+    out_len_ptr: *mut c_int,
+) {
+    if out_ptr.is_null() || out_len_ptr.is_null() {
+        return;
+    }
+    let ssl_ptr = match session_table().handles.lock().unwrap().get(&ssl_id) {
+        Some(&addr) => addr as *mut MESALINK_SSL<'_>,
+        None => return,
+    };
+    let ssl = unsafe { &mut *ssl_ptr };
+    if ssl.magic != MAGIC {
+        return;
+    }
+    let protocol = ssl.session.as_ref().and_then(|session| session.get_alpn_protocol());
+    unsafe {
+        match protocol {
+            Some(protocol) => {
+                *out_ptr = protocol.as_ptr();
+                *out_len_ptr = protocol.len() as c_int;
+            }
+            None => {
+                *out_ptr = ptr::null();
+                *out_len_ptr = 0;
+            }
+        }
+    }
+}
+
+/// Begins (or completes) a graceful shutdown. Sends our `close_notify`
+/// alert the first time it is called, and reports 0 once that is done but
+/// the peer's `close_notify` hasn't been observed yet, or 1 once both
+/// sides have sent theirs. Mirrors OpenSSL's `SSL_shutdown`, where the
+/// caller re-calls after reading until it gets 1.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_shutdown(ssl_id: u64) -> c_int {
+    sanitize_handle_return_fail!(ssl_id);
+    let ssl = unsafe { &mut *ssl_ptr };
+    if !ssl.sent_close_notify {
+        if let Some(session) = ssl.session.as_mut() {
+            session.send_close_notify();
+            match ssl.socket.as_mut() {
+                Some(socket) => {
+                    let _ = session.flush(socket);
+                }
+                /* No socket to flush to in memory-BIO mode: queue the
+                 * close_notify as ciphertext instead, for the caller to
+                 * drain with mesalink_SSL_write_tls like any other
+                 * outbound record. */
+                None => {
+                    let _ = session.write_tls(&mut ssl.tls_output_buffer);
+                }
+            }
+        }
+        ssl.sent_close_notify = true;
+    }
+    if ssl.received_close_notify {
+        SslConstants::SslSuccess as c_int
+    } else {
+        SslConstants::SslFailure as c_int
+    }
+}
+
+/// Returns the peer's leaf certificate from a completed handshake, or
+/// `NULL` if none was presented. The caller owns the result and must free
+/// it with `mesalink_X509_free`.
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_get_peer_certificate(ssl_id: u64) -> *mut MESALINK_X509 {
+    let ssl_ptr = match session_table().handles.lock().unwrap().get(&ssl_id) {
+        Some(&addr) => addr as *mut MESALINK_SSL<'_>,
+        None => return ptr::null_mut(),
+    };
+    let ssl = unsafe { &*ssl_ptr };
+    if ssl.magic != MAGIC {
+        return ptr::null_mut();
+    }
+    let leaf = ssl
+        .session
+        .as_ref()
+        .and_then(|session| session.get_peer_certificates())
+        .and_then(|certs| certs.into_iter().next());
+    match leaf {
+        Some(cert) => Box::into_raw(Box::new(MESALINK_X509 {
+            magic: MAGIC,
+            der: cert.0,
+        })),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Returns an X509-style verify result for the session's handshake:
+/// `MESALINK_X509_V_OK` once the handshake has completed successfully,
+/// `MESALINK_X509_V_ERR_UNSPECIFIED` otherwise (rustls aborts the
+/// handshake outright on a verification failure, so there is no separate
+/// "failed but proceeded anyway" state to report).
+#[no_mangle]
+pub extern "C" fn mesalink_SSL_get_verify_result(ssl_id: u64) -> c_int {
+    let ssl_ptr = match session_table().handles.lock().unwrap().get(&ssl_id) {
+        Some(&addr) => addr as *mut MESALINK_SSL<'_>,
+        None => return MESALINK_X509_V_ERR_UNSPECIFIED,
+    };
+    let ssl = unsafe { &*ssl_ptr };
+    if ssl.magic != MAGIC {
+        return MESALINK_X509_V_ERR_UNSPECIFIED;
+    }
+    match ssl.session.as_ref() {
+        Some(session) if !session.is_handshaking() => MESALINK_X509_V_OK,
+        _ => MESALINK_X509_V_ERR_UNSPECIFIED,
+    }
+}
+
+/// Reads back the DER bytes of an `MESALINK_X509` returned by
+/// `mesalink_SSL_get_peer_certificate`, e.g. for certificate pinning.
+/// `*out_ptr`/`*out_len_ptr` point into `x509` and are only valid for its
+/// lifetime; the caller must not free `x509` while still using them.
+#[no_mangle]
+pub extern "C" fn mesalink_X509_get_der(
+    x509_ptr: *mut MESALINK_X509,
+    out_ptr: *mut *const c_uchar,
+    out_len_ptr: *mut c_int,
+) {
+    if out_ptr.is_null() || out_len_ptr.is_null() {
+        return;
+    }
+    if x509_ptr.is_null() {
+        return;
+    }
+    let x509 = unsafe { &*x509_ptr };
+    if x509.magic != MAGIC {
+        return;
+    }
+    unsafe {
+        *out_ptr = x509.der.as_ptr();
+        *out_len_ptr = x509.der.len() as c_int;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn mesalink_X509_free(x509_ptr: *mut MESALINK_X509) {
+    let _ = unsafe { Box::from_raw(x509_ptr) };
+}
+
 #[no_mangle]
 pub extern "C" fn mesalink_CTX_free(ctx_ptr: *mut MESALINK_CTX) {
     let _ = unsafe { Box::from_raw(ctx_ptr) };
 }
 
+/// Closes and frees the session `ssl_id` refers to. The id is removed
+/// from the session table first, so a use-after-free would need a second,
+/// independently forged id rather than a dangling pointer.
 #[no_mangle]
-pub extern "C" fn mesalink_SSL_free<S: Session, T: Read + Write>(ssl_ptr: *mut MESALINK_SSL<S, T>) {
-    let _ = unsafe { Box::from_raw(ssl_ptr) };
+pub extern "C" fn mesalink_SSL_free(ssl_id: u64) {
+    let addr = session_table().handles.lock().unwrap().remove(&ssl_id);
+    if let Some(addr) = addr {
+        let _ = unsafe { Box::from_raw(addr as *mut MESALINK_SSL<'static>) };
+    }
 }
\ No newline at end of file